@@ -1,18 +1,31 @@
-use std::{fmt, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, KeyPair};
+use rustls::{server::ResolvesServerCert, sign::CertifiedKey};
+use subtle::ConstantTimeEq;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
 };
+use tokio_rustls::{TlsAcceptor, TlsConnector};
 use url::Url;
 
 /// Proxy errors that keep their underlying causes for better debugging
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum ProxyError {
     SocketCreateError(std::io::Error),
     SocketListenError(std::io::Error),
     ParsingError(String),
     SocketWriteError(std::io::Error),
     SocketReadError(std::io::Error),
+    UpstreamProxyError(String),
+    TlsError(String),
 }
 
 impl fmt::Display for ProxyError {
@@ -23,34 +36,347 @@ impl fmt::Display for ProxyError {
             ProxyError::ParsingError(msg)   => write!(f, "Failed to parse request: {}", msg),
             ProxyError::SocketWriteError(e)  => write!(f, "Failed to write to socket: {}", e),
             ProxyError::SocketReadError(e)   => write!(f, "Failed to read from socket: {}", e),
+            ProxyError::UpstreamProxyError(msg) => write!(f, "Upstream proxy chaining failed: {}", msg),
+            ProxyError::TlsError(msg) => write!(f, "TLS interception failed: {}", msg),
         }
     }
 }
 
 impl std::error::Error for ProxyError {}
 
+/// Which PROXY protocol wire format to prepend to the upstream connection.
+#[derive(Debug, Clone, Copy)]
+enum ProxyProtocolVersion {
+    /// Human-readable text header, e.g. `PROXY TCP4 ... \r\n`.
+    V1,
+    /// Compact binary header used by haproxy/nginx in newer deployments.
+    V2,
+}
+
+/// Builds a PROXY protocol header describing `client -> upstream`.
+fn build_proxy_header(version: ProxyProtocolVersion, client: SocketAddr, upstream: SocketAddr) -> Vec<u8> {
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let line = match (client, upstream) {
+                (SocketAddr::V4(c), SocketAddr::V4(u)) => format!(
+                    "PROXY TCP4 {} {} {} {}\r\n",
+                    c.ip(), u.ip(), c.port(), u.port()
+                ),
+                (SocketAddr::V6(c), SocketAddr::V6(u)) => format!(
+                    "PROXY TCP6 {} {} {} {}\r\n",
+                    c.ip(), u.ip(), c.port(), u.port()
+                ),
+                _ => "PROXY UNKNOWN\r\n".to_string(),
+            };
+            line.into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            const SIGNATURE: [u8; 12] = [
+                0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+            ];
+            let mut header = Vec::with_capacity(28);
+            header.extend_from_slice(&SIGNATURE);
+            header.push(0x21); // version 2, PROXY command
+            match (client, upstream) {
+                (SocketAddr::V4(c), SocketAddr::V4(u)) => {
+                    header.push(0x11); // AF_INET, STREAM
+                    header.extend_from_slice(&16u16.to_be_bytes());
+                    header.extend_from_slice(&c.ip().octets());
+                    header.extend_from_slice(&u.ip().octets());
+                    header.extend_from_slice(&c.port().to_be_bytes());
+                    header.extend_from_slice(&u.port().to_be_bytes());
+                }
+                (SocketAddr::V6(c), SocketAddr::V6(u)) => {
+                    header.push(0x21); // AF_INET6, STREAM
+                    header.extend_from_slice(&36u16.to_be_bytes());
+                    header.extend_from_slice(&c.ip().octets());
+                    header.extend_from_slice(&u.ip().octets());
+                    header.extend_from_slice(&c.port().to_be_bytes());
+                    header.extend_from_slice(&u.port().to_be_bytes());
+                }
+                _ => {
+                    header.push(0x00); // AF_UNSPEC
+                    header.extend_from_slice(&0u16.to_be_bytes());
+                }
+            }
+            header
+        }
+    }
+}
+
+/// Builds a fixed-format SOCKS5 reply for `code`, with BND.ADDR/BND.PORT zeroed.
+fn socks5_reply(code: u8) -> [u8; 10] {
+    [0x05, code, 0x00, 0x01, 0, 0, 0, 0, 0, 0]
+}
+
+/// A parent HTTP proxy that roxy should chain outbound connections through.
+struct UpstreamProxyConfig {
+    address: String,
+    /// `Proxy-Authorization` header value to present to the parent proxy.
+    proxy_authorization: Option<String>,
+}
+
+/// Inserts a `Proxy-Authorization` header right after the request line.
+fn inject_proxy_authorization(data: &[u8], value: &str) -> Vec<u8> {
+    let split_at = data
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| i + 2)
+        .unwrap_or(data.len());
+
+    let mut out = Vec::with_capacity(data.len() + value.len() + 21);
+    out.extend_from_slice(&data[..split_at]);
+    out.extend_from_slice(format!("Proxy-Authorization: {value}\r\n").as_bytes());
+    out.extend_from_slice(&data[split_at..]);
+    out
+}
+
+/// Signs a fresh leaf certificate for `host` using the configured CA.
+fn generate_leaf_cert(ca: &Certificate, host: &str) -> Result<CertifiedKey, ProxyError> {
+    let mut params = CertificateParams::new(vec![host.to_string()]);
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, host);
+
+    let leaf = Certificate::from_params(params)
+        .map_err(|e| ProxyError::TlsError(format!("failed to build leaf cert for '{}': {}", host, e)))?;
+    let leaf_der = leaf
+        .serialize_der_with_signer(ca)
+        .map_err(|e| ProxyError::TlsError(format!("failed to sign leaf cert for '{}': {}", host, e)))?;
+    let key_der = leaf.serialize_private_key_der();
+
+    let cert_chain = vec![rustls::Certificate(leaf_der)];
+    let key = rustls::PrivateKey(key_der);
+    let signing_key = rustls::sign::any_supported_type(&key)
+        .map_err(|e| ProxyError::TlsError(format!("unsupported leaf key for '{}': {}", host, e)))?;
+
+    Ok(CertifiedKey::new(cert_chain, signing_key))
+}
+
+/// Resolves a per-SNI leaf certificate, generating and caching it on demand.
+struct HostCertResolver {
+    ca_cert: Certificate,
+    cache: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for HostCertResolver {
+    fn resolve(&self, client_hello: rustls::server::ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello.server_name()?.to_string();
+
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(certified) = cache.get(&host) {
+            return Some(Arc::clone(certified));
+        }
+
+        let certified = Arc::new(generate_leaf_cert(&self.ca_cert, &host).ok()?);
+        cache.insert(host, Arc::clone(&certified));
+        Some(certified)
+    }
+}
+
+/// TLS machinery for intercepting CONNECT tunnels.
+struct TlsInterceptConfig {
+    acceptor: TlsAcceptor,
+    origin_connector: TlsConnector,
+}
+
+impl TlsInterceptConfig {
+    /// Loads the CA cert/key PEM files used to sign per-host leaf certs.
+    fn load(ca_cert_path: &str, ca_key_path: &str) -> Result<TlsInterceptConfig, ProxyError> {
+        let cert_pem = std::fs::read_to_string(ca_cert_path)
+            .map_err(|e| ProxyError::TlsError(format!("failed to read CA cert '{}': {}", ca_cert_path, e)))?;
+        let key_pem = std::fs::read_to_string(ca_key_path)
+            .map_err(|e| ProxyError::TlsError(format!("failed to read CA key '{}': {}", ca_key_path, e)))?;
+
+        let key_pair = KeyPair::from_pem(&key_pem)
+            .map_err(|e| ProxyError::TlsError(format!("failed to parse CA key: {}", e)))?;
+        let ca_params = CertificateParams::from_ca_cert_pem(&cert_pem, key_pair)
+            .map_err(|e| ProxyError::TlsError(format!("failed to parse CA cert: {}", e)))?;
+        let ca_cert = Certificate::from_params(ca_params)
+            .map_err(|e| ProxyError::TlsError(format!("failed to load CA: {}", e)))?;
+
+        let resolver = Arc::new(HostCertResolver {
+            ca_cert,
+            cache: Mutex::new(HashMap::new()),
+        });
+
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+
+        Ok(TlsInterceptConfig {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            origin_connector: TlsConnector::from(Arc::new(client_config)),
+        })
+    }
+}
+
+/// Extracts the `user:pass` pair from a `Proxy-Authorization: Basic <b64>` header.
+fn extract_basic_credentials(headers: &[httparse::Header]) -> Option<(String, String)> {
+    let header = headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Proxy-Authorization"))?;
+    let value = std::str::from_utf8(header.value).ok()?;
+    let b64 = value.strip_prefix("Basic ")?;
+    let decoded = STANDARD.decode(b64.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, pass) = decoded.split_once(':')?;
+    Some((user.to_string(), pass.to_string()))
+}
+
+/// Compares two credentials in constant time.
+fn credentials_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len() && expected.as_bytes().ct_eq(actual.as_bytes()).into()
+}
+
+/// Rebuilds a request from its parsed headers and body, omitting `Proxy-Authorization`.
+fn strip_proxy_authorization(req: &httparse::Request, body: &[u8]) -> Vec<u8> {
+    let method = req.method.unwrap_or("");
+    let path = req.path.unwrap_or("");
+    let version = req.version.unwrap_or(1);
+
+    let mut out = format!("{method} {path} HTTP/1.{version}\r\n").into_bytes();
+    for header in req.headers.iter() {
+        if header.name.eq_ignore_ascii_case("Proxy-Authorization") {
+            continue;
+        }
+        out.extend_from_slice(header.name.as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(header.value);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(body);
+    out
+}
+
+/// Default ceiling on how large an accumulated request head is allowed to grow.
+const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+
 struct ProxyServer {
     sock: TcpListener,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    credentials: Option<HashMap<String, String>>,
+    upstream_proxy: Option<UpstreamProxyConfig>,
+    max_header_bytes: usize,
+    tls_intercept: Option<TlsInterceptConfig>,
 }
 
 impl ProxyServer {
-    pub async fn new(address: String) -> Result<ProxyServer, ProxyError> {
+    pub async fn new(
+        address: String,
+        proxy_protocol: Option<ProxyProtocolVersion>,
+        credentials: Option<HashMap<String, String>>,
+        upstream_proxy: Option<UpstreamProxyConfig>,
+        max_header_bytes: usize,
+        tls_intercept_ca: Option<(String, String)>,
+    ) -> Result<ProxyServer, ProxyError> {
         println!("[+] Creating proxy server on {}", address);
         let sock = TcpListener::bind(address)
             .await
             .map_err(ProxyError::SocketCreateError)?;
-        Ok(ProxyServer { sock })
+        let tls_intercept = match tls_intercept_ca {
+            Some((ca_cert_path, ca_key_path)) => {
+                Some(TlsInterceptConfig::load(&ca_cert_path, &ca_key_path)?)
+            }
+            None => None,
+        };
+        Ok(ProxyServer {
+            sock,
+            proxy_protocol,
+            credentials,
+            upstream_proxy,
+            max_header_bytes,
+            tls_intercept,
+        })
+    }
+
+    /// Checks a `Proxy-Authorization` header against the credential store.
+    fn authorized(&self, headers: &[httparse::Header]) -> bool {
+        let Some(store) = &self.credentials else {
+            return true;
+        };
+        match extract_basic_credentials(headers) {
+            Some((user, pass)) => store.get(&user).is_some_and(|expected| credentials_match(expected, &pass)),
+            None => false,
+        }
     }
 
-    async fn run(host: String, port: String, mut cstream: TcpStream, https: bool, first_req: Vec<u8>) -> Result<(), ProxyError> {
+    async fn run(
+        &self,
+        host: String,
+        port: String,
+        client_addr: SocketAddr,
+        mut cstream: TcpStream,
+        https: bool,
+        first_req: Vec<u8>,
+    ) -> Result<(), ProxyError> {
 
-        let mut sstream = TcpStream::connect(format!("{}:{}", host, port))
-            .await
-            .map_err(ProxyError::SocketCreateError)?;
+        let mut sstream = TcpStream::connect(match &self.upstream_proxy {
+            Some(parent) => parent.address.clone(),
+            None => format!("{}:{}", host, port),
+        })
+        .await
+        .map_err(ProxyError::SocketCreateError)?;
+
+        if let Some(parent) = &self.upstream_proxy {
+            if https {
+                let mut connect_req = format!(
+                    "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n",
+                );
+                if let Some(auth) = &parent.proxy_authorization {
+                    connect_req.push_str(&format!("Proxy-Authorization: {}\r\n", auth));
+                }
+                connect_req.push_str("\r\n");
+
+                sstream
+                    .write_all(connect_req.as_bytes())
+                    .await
+                    .map_err(ProxyError::SocketWriteError)?;
+
+                let resp_buf = self.read_connect_response(&mut sstream).await?;
+                let resp = String::from_utf8_lossy(&resp_buf);
+                let status_line = resp.lines().next().unwrap_or("");
+                if !status_line.contains(" 200 ") && !status_line.ends_with(" 200") {
+                    return Err(ProxyError::UpstreamProxyError(format!(
+                        "parent proxy rejected CONNECT: {}",
+                        status_line
+                    )));
+                }
+            }
+        }
+
+        if let Some(version) = self.proxy_protocol {
+            let upstream_addr = sstream
+                .peer_addr()
+                .map_err(ProxyError::SocketCreateError)?;
+            let header = build_proxy_header(version, client_addr, upstream_addr);
+            sstream
+                .write_all(&header)
+                .await
+                .map_err(ProxyError::SocketWriteError)?;
+        }
 
         if !https {
+            let req_bytes = match self.upstream_proxy.as_ref().and_then(|p| p.proxy_authorization.as_deref()) {
+                Some(auth) => inject_proxy_authorization(&first_req, auth),
+                None => first_req,
+            };
             sstream
-                .write_all(&first_req)
+                .write_all(&req_bytes)
                 .await
                 .map_err(ProxyError::SocketWriteError)?;
         } else {
@@ -58,6 +384,10 @@ impl ProxyServer {
                 .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
                 .await
                 .map_err(ProxyError::SocketWriteError)?;
+
+            if let Some(tls) = &self.tls_intercept {
+                return self.intercept_tls(host, cstream, sstream, tls).await;
+            }
         }
 
         // Bi-directional copy between client and server
@@ -68,44 +398,251 @@ impl ProxyServer {
         Ok(())
     }
 
-    async fn process(&self, mut stream: TcpStream) -> Result<(), ProxyError> {
-        let mut buffer = [0; 4096];
-        let bytes_read = stream
-            .read(&mut buffer)
+    /// Terminates the CONNECT tunnel's TLS on both sides and copies decrypted bytes.
+    async fn intercept_tls(
+        &self,
+        host: String,
+        cstream: TcpStream,
+        sstream: TcpStream,
+        tls: &TlsInterceptConfig,
+    ) -> Result<(), ProxyError> {
+        let mut client_tls = tls
+            .acceptor
+            .accept(cstream)
+            .await
+            .map_err(|e| ProxyError::TlsError(format!("client handshake for '{}' failed: {}", host, e)))?;
+
+        let server_name = rustls::ServerName::try_from(host.as_str())
+            .map_err(|e| ProxyError::TlsError(format!("invalid SNI host '{}': {}", host, e)))?;
+        let mut origin_tls = tls
+            .origin_connector
+            .connect(server_name, sstream)
+            .await
+            .map_err(|e| ProxyError::TlsError(format!("origin handshake for '{}' failed: {}", host, e)))?;
+
+        tokio::io::copy_bidirectional(&mut client_tls, &mut origin_tls)
             .await
             .map_err(ProxyError::SocketReadError)?;
 
-        if bytes_read == 0 {
-            return Err(ProxyError::SocketReadError(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "Client closed connection",
-            )));
+        Ok(())
+    }
+
+    /// Handles a SOCKS5 client: method negotiation, optional auth, then CONNECT.
+    async fn handle_socks5(&self, mut stream: TcpStream, client_addr: SocketAddr) -> Result<(), ProxyError> {
+        println!("[SOCKS5] connection from {}", client_addr);
+
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await.map_err(ProxyError::SocketReadError)?;
+        let mut methods = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut methods).await.map_err(ProxyError::SocketReadError)?;
+
+        if self.credentials.is_some() {
+            if !methods.contains(&0x02) {
+                stream.write_all(&[0x05, 0xFF]).await.map_err(ProxyError::SocketWriteError)?;
+                return Ok(());
+            }
+            stream.write_all(&[0x05, 0x02]).await.map_err(ProxyError::SocketWriteError)?;
+
+            let mut ver_ulen = [0u8; 2];
+            stream.read_exact(&mut ver_ulen).await.map_err(ProxyError::SocketReadError)?;
+            let mut uname = vec![0u8; ver_ulen[1] as usize];
+            stream.read_exact(&mut uname).await.map_err(ProxyError::SocketReadError)?;
+            let mut plen = [0u8; 1];
+            stream.read_exact(&mut plen).await.map_err(ProxyError::SocketReadError)?;
+            let mut passwd = vec![0u8; plen[0] as usize];
+            stream.read_exact(&mut passwd).await.map_err(ProxyError::SocketReadError)?;
+
+            let user = String::from_utf8_lossy(&uname).to_string();
+            let pass = String::from_utf8_lossy(&passwd).to_string();
+            let authorized = self
+                .credentials
+                .as_ref()
+                .is_some_and(|store| store.get(&user).is_some_and(|expected| credentials_match(expected, &pass)));
+
+            stream
+                .write_all(&[0x01, if authorized { 0x00 } else { 0x01 }])
+                .await
+                .map_err(ProxyError::SocketWriteError)?;
+            if !authorized {
+                return Ok(());
+            }
+        } else {
+            if !methods.contains(&0x00) {
+                stream.write_all(&[0x05, 0xFF]).await.map_err(ProxyError::SocketWriteError)?;
+                return Ok(());
+            }
+            stream.write_all(&[0x05, 0x00]).await.map_err(ProxyError::SocketWriteError)?;
+        }
+
+        let mut req = [0u8; 4];
+        stream.read_exact(&mut req).await.map_err(ProxyError::SocketReadError)?;
+        let cmd = req[1];
+        let atyp = req[3];
+
+        let (host, port) = match atyp {
+            0x01 => {
+                let mut addr = [0u8; 4];
+                stream.read_exact(&mut addr).await.map_err(ProxyError::SocketReadError)?;
+                let mut port_buf = [0u8; 2];
+                stream.read_exact(&mut port_buf).await.map_err(ProxyError::SocketReadError)?;
+                (std::net::Ipv4Addr::from(addr).to_string(), u16::from_be_bytes(port_buf))
+            }
+            0x03 => {
+                let mut len_buf = [0u8; 1];
+                stream.read_exact(&mut len_buf).await.map_err(ProxyError::SocketReadError)?;
+                let mut domain = vec![0u8; len_buf[0] as usize];
+                stream.read_exact(&mut domain).await.map_err(ProxyError::SocketReadError)?;
+                let mut port_buf = [0u8; 2];
+                stream.read_exact(&mut port_buf).await.map_err(ProxyError::SocketReadError)?;
+                (String::from_utf8_lossy(&domain).to_string(), u16::from_be_bytes(port_buf))
+            }
+            0x04 => {
+                let mut addr = [0u8; 16];
+                stream.read_exact(&mut addr).await.map_err(ProxyError::SocketReadError)?;
+                let mut port_buf = [0u8; 2];
+                stream.read_exact(&mut port_buf).await.map_err(ProxyError::SocketReadError)?;
+                (std::net::Ipv6Addr::from(addr).to_string(), u16::from_be_bytes(port_buf))
+            }
+            _ => {
+                let _ = stream.write_all(&socks5_reply(0x08)).await;
+                return Ok(());
+            }
+        };
+
+        if cmd != 0x01 {
+            let _ = stream.write_all(&socks5_reply(0x07)).await;
+            return Ok(());
+        }
+
+        println!("[SOCKS5] CONNECT {}:{}", host, port);
+
+        match TcpStream::connect(format!("{}:{}", host, port)).await {
+            Ok(mut sstream) => {
+                stream
+                    .write_all(&socks5_reply(0x00))
+                    .await
+                    .map_err(ProxyError::SocketWriteError)?;
+                tokio::io::copy_bidirectional(&mut stream, &mut sstream)
+                    .await
+                    .map_err(ProxyError::SocketReadError)?;
+            }
+            Err(_) => {
+                let _ = stream.write_all(&socks5_reply(0x04)).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a parent proxy's CONNECT response until the `\r\n\r\n` terminator.
+    async fn read_connect_response(&self, stream: &mut TcpStream) -> Result<Vec<u8>, ProxyError> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+            if buffer.len() > self.max_header_bytes {
+                return Err(ProxyError::UpstreamProxyError(format!(
+                    "parent proxy CONNECT response exceeds the {}-byte limit",
+                    self.max_header_bytes
+                )));
+            }
+
+            let n = stream
+                .read(&mut chunk)
+                .await
+                .map_err(ProxyError::SocketReadError)?;
+
+            if n == 0 {
+                return Err(ProxyError::UpstreamProxyError(
+                    "parent proxy closed connection before completing CONNECT response".into(),
+                ));
+            }
+
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+
+        Ok(buffer)
+    }
+
+    /// Reads from `stream` until the `\r\n\r\n` header terminator is seen.
+    async fn read_request_head(&self, stream: &mut TcpStream) -> Result<Vec<u8>, ProxyError> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+            if buffer.len() > self.max_header_bytes {
+                return Err(ProxyError::ParsingError(format!(
+                    "Request header exceeds the {}-byte limit",
+                    self.max_header_bytes
+                )));
+            }
+
+            let n = stream
+                .read(&mut chunk)
+                .await
+                .map_err(ProxyError::SocketReadError)?;
+
+            if n == 0 {
+                return Err(ProxyError::SocketReadError(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Client closed connection",
+                )));
+            }
+
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+
+        Ok(buffer)
+    }
+
+    async fn process(&self, mut stream: TcpStream, client_addr: SocketAddr) -> Result<(), ProxyError> {
+        let mut probe = [0u8; 1];
+        let peeked = stream.peek(&mut probe).await.map_err(ProxyError::SocketReadError)?;
+        if peeked > 0 && probe[0] == 0x05 {
+            return self.handle_socks5(stream, client_addr).await;
         }
 
-        let req = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
+        let buffer = self.read_request_head(&mut stream).await?;
 
-        // Get the request head
-        let head = req
-            .split("\r\n")
-            .next()
-            .ok_or_else(|| ProxyError::ParsingError("Missing request head".into()))?;
+        let mut header_slots = [httparse::EMPTY_HEADER; 64];
+        let mut parsed = httparse::Request::new(&mut header_slots);
+        let body_offset = match parsed
+            .parse(&buffer)
+            .map_err(|e| ProxyError::ParsingError(format!("Malformed request: {}", e)))?
+        {
+            httparse::Status::Complete(offset) => offset,
+            httparse::Status::Partial => {
+                return Err(ProxyError::ParsingError(
+                    "Request head incomplete".into(),
+                ));
+            }
+        };
 
-        // Get method
-        let met = head
-            .split(" ")
-            .nth(0)
-            .ok_or_else(|| ProxyError::ParsingError("Missing HTTP method".into()))?;
+        let met = parsed
+            .method
+            .ok_or_else(|| ProxyError::ParsingError("Missing HTTP method".into()))?
+            .to_string();
+        let url = parsed
+            .path
+            .ok_or_else(|| ProxyError::ParsingError("Missing URL".into()))?
+            .to_string();
+
+        if !self.authorized(parsed.headers) {
+            stream
+                .write_all(
+                    b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"roxy\"\r\n\r\n",
+                )
+                .await
+                .map_err(ProxyError::SocketWriteError)?;
+            return Ok(());
+        }
 
-        // Get the URL
-        let url = head
-            .split(" ")
-            .nth(1)
-            .ok_or_else(|| ProxyError::ParsingError("Missing URL".into()))?;
+        let first_req = strip_proxy_authorization(&parsed, &buffer[body_offset..]);
 
-        match met {
+        match met.as_str() {
             "GET" | "POST" | "PUT" | "DELETE" | "HEAD" | "OPTIONS" => {
 
-                let info = Url::parse(url)
+                let info = Url::parse(&url)
                     .map_err(|e| ProxyError::ParsingError(format!("Bad URL '{}': {}", url, e)))?;
                 let host = info
                     .host_str()
@@ -115,12 +652,13 @@ impl ProxyServer {
                     .ok_or_else(|| ProxyError::ParsingError("Missing port".into()))?;
                 println!("[HTTP] {} {} (host={}, port={})", met, url, host, port);
 
-                ProxyServer::run(
+                self.run(
                     host.to_string(),
                     port.to_string(),
+                    client_addr,
                     stream,
                     false,
-                    buffer[..bytes_read].to_vec(),
+                    first_req,
                 )
                 .await?;
             }
@@ -140,12 +678,13 @@ impl ProxyServer {
 
                 println!("[HTTPS] {} {} (host={}, port={})", met, url, host, port);
 
-                ProxyServer::run(
+                self.run(
                     host.to_string(),
                     port.to_string(),
+                    client_addr,
                     stream,
                     true,
-                    buffer[..bytes_read].to_vec()).await?;
+                    first_req).await?;
             }
 
             _ => {
@@ -162,7 +701,7 @@ impl ProxyServer {
     pub async fn listen(self: Arc<Self>) -> Result<(), ProxyError> {
         println!("[+] Listening for incoming connections...");
         loop {
-            let (stream, _) = self
+            let (stream, peer_addr) = self
                 .sock
                 .accept()
                 .await
@@ -171,7 +710,7 @@ impl ProxyServer {
 
             let server_ref = Arc::clone(&self);
             tokio::spawn(async move {
-                if let Err(err) = server_ref.process(stream).await {
+                if let Err(err) = server_ref.process(stream, peer_addr).await {
                     eprintln!("[!] Error processing connection: {}", err);
                 }
             });
@@ -179,9 +718,200 @@ impl ProxyServer {
     }
 }
 
+/// Command-line configuration for the `roxy` binary.
+struct CliConfig {
+    listen_addr: String,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    credentials: Option<HashMap<String, String>>,
+    upstream_proxy: Option<UpstreamProxyConfig>,
+    tls_intercept_ca: Option<(String, String)>,
+}
+
+impl CliConfig {
+    /// Parses flags from the process arguments, falling back to defaults.
+    fn parse() -> Result<CliConfig, ProxyError> {
+        let mut listen_addr = "127.0.0.1:9999".to_string();
+        let mut proxy_protocol = None;
+        let mut credentials: Option<HashMap<String, String>> = None;
+        let mut upstream_proxy_addr = None;
+        let mut upstream_proxy_auth = None;
+        let mut tls_intercept_ca = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--listen" => {
+                    listen_addr = args.next().ok_or_else(|| {
+                        ProxyError::ParsingError("--listen requires an address".into())
+                    })?;
+                }
+                "--proxy-protocol" => {
+                    let value = args.next().ok_or_else(|| {
+                        ProxyError::ParsingError("--proxy-protocol requires v1 or v2".into())
+                    })?;
+                    proxy_protocol = Some(match value.as_str() {
+                        "v1" => ProxyProtocolVersion::V1,
+                        "v2" => ProxyProtocolVersion::V2,
+                        other => {
+                            return Err(ProxyError::ParsingError(format!(
+                                "Unknown --proxy-protocol value '{}', expected v1 or v2",
+                                other
+                            )))
+                        }
+                    });
+                }
+                "--credential" => {
+                    let value = args.next().ok_or_else(|| {
+                        ProxyError::ParsingError("--credential requires a user:pass pair".into())
+                    })?;
+                    let (user, pass) = value.split_once(':').ok_or_else(|| {
+                        ProxyError::ParsingError(format!(
+                            "--credential value '{}' must be in user:pass form",
+                            value
+                        ))
+                    })?;
+                    credentials
+                        .get_or_insert_with(HashMap::new)
+                        .insert(user.to_string(), pass.to_string());
+                }
+                "--upstream-proxy" => {
+                    upstream_proxy_addr = Some(args.next().ok_or_else(|| {
+                        ProxyError::ParsingError("--upstream-proxy requires a host:port".into())
+                    })?);
+                }
+                "--upstream-proxy-auth" => {
+                    upstream_proxy_auth = Some(args.next().ok_or_else(|| {
+                        ProxyError::ParsingError(
+                            "--upstream-proxy-auth requires a Proxy-Authorization value".into(),
+                        )
+                    })?);
+                }
+                "--tls-intercept-ca" => {
+                    let cert_path = args.next().ok_or_else(|| {
+                        ProxyError::ParsingError(
+                            "--tls-intercept-ca requires a CA cert path and a CA key path".into(),
+                        )
+                    })?;
+                    let key_path = args.next().ok_or_else(|| {
+                        ProxyError::ParsingError(
+                            "--tls-intercept-ca requires a CA key path after the cert path".into(),
+                        )
+                    })?;
+                    tls_intercept_ca = Some((cert_path, key_path));
+                }
+                other => {
+                    return Err(ProxyError::ParsingError(format!(
+                        "Unknown argument '{}'",
+                        other
+                    )))
+                }
+            }
+        }
+
+        let upstream_proxy = match upstream_proxy_addr {
+            Some(address) => Some(UpstreamProxyConfig {
+                address,
+                proxy_authorization: upstream_proxy_auth,
+            }),
+            None if upstream_proxy_auth.is_some() => {
+                return Err(ProxyError::ParsingError(
+                    "--upstream-proxy-auth requires --upstream-proxy".into(),
+                ));
+            }
+            None => None,
+        };
+
+        if proxy_protocol.is_some() && upstream_proxy.is_some() {
+            return Err(ProxyError::ParsingError(
+                "--proxy-protocol can't be combined with --upstream-proxy: the header would land on the parent proxy's socket instead of the real destination".into(),
+            ));
+        }
+        if proxy_protocol.is_some() && tls_intercept_ca.is_some() {
+            return Err(ProxyError::ParsingError(
+                "--proxy-protocol can't be combined with --tls-intercept-ca: the header would corrupt the TLS handshake with the origin".into(),
+            ));
+        }
+
+        Ok(CliConfig {
+            listen_addr,
+            proxy_protocol,
+            credentials,
+            upstream_proxy,
+            tls_intercept_ca,
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), ProxyError> {
-    let server = Arc::new(ProxyServer::new("127.0.0.1:9999".to_string()).await?);
+    let cli = CliConfig::parse()?;
+    let server = Arc::new(
+        ProxyServer::new(
+            cli.listen_addr,
+            cli.proxy_protocol,
+            cli.credentials,
+            cli.upstream_proxy,
+            DEFAULT_MAX_HEADER_BYTES,
+            cli.tls_intercept_ca,
+        )
+        .await?,
+    );
     server.listen().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_header_v1_ipv4() {
+        let client: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let upstream: SocketAddr = "10.0.0.2:80".parse().unwrap();
+        let header = build_proxy_header(ProxyProtocolVersion::V1, client, upstream);
+        assert_eq!(header, b"PROXY TCP4 10.0.0.1 10.0.0.2 4000 80\r\n");
+    }
+
+    #[test]
+    fn proxy_header_v1_mixed_families_is_unknown() {
+        let client: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let upstream: SocketAddr = "[::1]:80".parse().unwrap();
+        let header = build_proxy_header(ProxyProtocolVersion::V1, client, upstream);
+        assert_eq!(header, b"PROXY UNKNOWN\r\n");
+    }
+
+    #[test]
+    fn proxy_header_v2_ipv4_layout() {
+        let client: SocketAddr = "192.168.0.1:1111".parse().unwrap();
+        let upstream: SocketAddr = "192.168.0.2:2222".parse().unwrap();
+        let header = build_proxy_header(ProxyProtocolVersion::V2, client, upstream);
+
+        assert_eq!(
+            &header[..12],
+            &[0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A]
+        );
+        assert_eq!(header[12], 0x21); // version 2, PROXY command
+        assert_eq!(header[13], 0x11); // AF_INET, STREAM
+        assert_eq!(&header[14..16], &16u16.to_be_bytes());
+        assert_eq!(&header[16..20], &[192, 168, 0, 1]);
+        assert_eq!(&header[20..24], &[192, 168, 0, 2]);
+        assert_eq!(&header[24..26], &1111u16.to_be_bytes());
+        assert_eq!(&header[26..28], &2222u16.to_be_bytes());
+    }
+
+    #[test]
+    fn socks5_reply_codes() {
+        assert_eq!(
+            socks5_reply(0x00),
+            [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            socks5_reply(0x07),
+            [0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            socks5_reply(0x08),
+            [0x05, 0x08, 0x00, 0x01, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}